@@ -1,14 +1,57 @@
 #![no_main]
 
+mod store;
+
+use candid::{candid_method, Principal};
 use ic_cdk::api::call::arg_data_raw;
 use ic_cdk::println;
-use ic_cdk_macros::{post_upgrade, query};
-use ic_stable_structures::{DefaultMemoryImpl, StableCell};
+use ic_cdk_macros::{post_upgrade, query, update};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
 use std::cell::RefCell;
+use store::{Config, GreeterStore, StableCellStore};
+
+const GREET_COUNTS_MEMORY_ID: MemoryId = MemoryId::new(1);
+const GREETING_TEMPLATES_MEMORY_ID: MemoryId = MemoryId::new(2);
+
+const DEFAULT_TEMPLATE: &str = "{greeter}, {greeted}!";
 
 thread_local! {
-    static GREETER: RefCell<StableCell<String, DefaultMemoryImpl>> =
-           RefCell::new(StableCell::init(DefaultMemoryImpl::default(),"Hello".to_string()).unwrap());
+    static GREET_COUNTS: RefCell<StableBTreeMap<Principal, u64, store::Memory>> =
+        RefCell::new(StableBTreeMap::init(store::memory(GREET_COUNTS_MEMORY_ID)));
+
+    static GREETING_TEMPLATES: RefCell<StableBTreeMap<String, String, store::Memory>> =
+        RefCell::new(StableBTreeMap::init(store::memory(GREETING_TEMPLATES_MEMORY_ID)));
+}
+
+/// Renders `template`, replacing every `{ident}` token with the matching
+/// entry in `vars`. Tokens with no matching entry are left as-is.
+fn render_template(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            out.push_str(rest);
+            return out;
+        };
+        let end = start + end;
+
+        out.push_str(&rest[..start]);
+        let ident = &rest[start + 1..end];
+        match vars.iter().find(|(name, _)| *name == ident) {
+            Some((_, value)) => out.push_str(value),
+            None => out.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+fn init_impl<S: GreeterStore>(store: &S, greeter: String) {
+    store.save_config(Config { greeter });
 }
 
 #[export_name = "canister_init"]
@@ -26,11 +69,10 @@ fn init() {
             candid::decode_args(&arg_raw).expect("Could not decode init args");
         arg.unwrap_or(default_greeter)
     };
-    GREETER.with_borrow_mut(|grt| grt.set(greeter)).unwrap();
 
-    let val = GREETER.with_borrow(|grt| grt.get().clone());
+    init_impl(&StableCellStore, greeter.clone());
 
-    println!("Init with greeter: {val}");
+    println!("Init with greeter: {greeter}");
 }
 
 #[post_upgrade]
@@ -38,10 +80,166 @@ fn post_upgrade() {
     init()
 }
 
+fn hello_impl<S: GreeterStore>(store: &S, greeted: &str, caller: &str, tmpl: &str) -> String {
+    let greeter = store.get();
+
+    render_template(
+        tmpl,
+        &[("greeter", &greeter), ("greeted", greeted), ("caller", caller)],
+    )
+}
+
 #[query]
-fn hello(arg: Option<String>) -> String {
-    let greeter = GREETER.with_borrow(|grt| grt.get().clone());
+#[candid_method(query)]
+fn hello(arg: Option<String>, template: Option<String>) -> String {
     let greeted = arg.unwrap_or("World".to_string());
+    let caller = ic_cdk::caller().to_text();
+
+    let tmpl = template
+        .and_then(|name| GREETING_TEMPLATES.with_borrow(|t| t.get(&name)))
+        .unwrap_or(DEFAULT_TEMPLATE.to_string());
+
+    hello_impl(&StableCellStore, &greeted, &caller, &tmpl)
+}
+
+fn get_greeter_impl<S: GreeterStore>(store: &S) -> String {
+    store.get()
+}
+
+#[query]
+#[candid_method(query)]
+fn get_greeter() -> String {
+    get_greeter_impl(&StableCellStore)
+}
+
+fn hello_and_count_impl<S: GreeterStore>(store: &S, greeted: &str, n: u64) -> String {
+    let greeter = store.get();
+
+    format!("{greeter}, {greeted}! You are visitor number {n}.")
+}
+
+#[update]
+#[candid_method(update)]
+fn hello_and_count(arg: Option<String>) -> String {
+    let greeted = arg.unwrap_or("World".to_string());
+
+    let caller = ic_cdk::caller();
+    let n = GREET_COUNTS.with_borrow_mut(|counts| {
+        let n = counts.get(&caller).unwrap_or(0) + 1;
+        counts.insert(caller, n);
+        n
+    });
+
+    hello_and_count_impl(&StableCellStore, &greeted, n)
+}
+
+#[update]
+#[candid_method(update)]
+fn set_template(name: String, tmpl: String) {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        ic_cdk::trap("Only a controller can update a template");
+    }
+
+    GREETING_TEMPLATES.with_borrow_mut(|t| t.insert(name, tmpl));
+}
+
+fn set_greeter_impl<S: GreeterStore>(store: &mut S, new: String) {
+    store.set(new);
+}
+
+#[update]
+#[candid_method(update)]
+fn set_greeter(new: String) {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        ic_cdk::trap("Only a controller can update the greeter");
+    }
+
+    set_greeter_impl(&mut StableCellStore, new);
+}
+
+candid::export_service!();
+
+// Reflects the methods above into a Candid service definition so callers
+// (e.g. the Terraform provider) can fetch it straight from the deployed
+// canister instead of relying on a hand-maintained .did file.
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct InMemoryStore {
+        greeter: RefCell<String>,
+    }
+
+    impl InMemoryStore {
+        fn with_greeter(greeter: &str) -> Self {
+            InMemoryStore {
+                greeter: RefCell::new(greeter.to_string()),
+            }
+        }
+    }
+
+    impl GreeterStore for InMemoryStore {
+        fn get(&self) -> String {
+            self.greeter.borrow().clone()
+        }
+
+        fn set(&mut self, v: String) {
+            *self.greeter.borrow_mut() = v;
+        }
+
+        fn save_config(&self, cfg: Config) {
+            *self.greeter.borrow_mut() = cfg.greeter;
+        }
+    }
+
+    #[test]
+    fn hello_impl_renders_the_stored_greeter() {
+        let store = InMemoryStore::with_greeter("Hi");
+
+        let greeting = hello_impl(&store, "Alice", "aaaaa-aa", "{greeter}, {greeted}!");
+
+        assert_eq!(greeting, "Hi, Alice!");
+    }
+
+    #[test]
+    fn hello_impl_substitutes_the_caller_placeholder() {
+        let store = InMemoryStore::with_greeter("Hi");
+
+        let greeting = hello_impl(&store, "Alice", "aaaaa-aa", "{greeter} to {caller}");
+
+        assert_eq!(greeting, "Hi to aaaaa-aa");
+    }
+
+    #[test]
+    fn get_and_set_greeter_impl_round_trip_through_the_store() {
+        let mut store = InMemoryStore::default();
+
+        set_greeter_impl(&mut store, "Howdy".to_string());
+
+        assert_eq!(get_greeter_impl(&store), "Howdy");
+    }
+
+    #[test]
+    fn hello_and_count_impl_reports_the_given_visitor_number() {
+        let store = InMemoryStore::with_greeter("Hi");
+
+        let greeting = hello_and_count_impl(&store, "Alice", 3);
+
+        assert_eq!(greeting, "Hi, Alice! You are visitor number 3.");
+    }
+
+    #[test]
+    fn init_impl_saves_the_greeter_via_save_config() {
+        let store = InMemoryStore::default();
+
+        init_impl(&store, "Howdy".to_string());
 
-    format!("{greeter}, {greeted}!")
+        assert_eq!(get_greeter_impl(&store), "Howdy");
+    }
 }
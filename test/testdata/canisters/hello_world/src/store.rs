@@ -0,0 +1,90 @@
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Memory as _, StableCell};
+use std::cell::RefCell;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const GREETER_MEMORY_ID: MemoryId = MemoryId::new(0);
+
+/// Magic bytes `ic-stable-structures` writes at the start of stable memory it
+/// manages. Mirrors the crate's own (private) `MemoryManager` layout magic;
+/// used here only to detect memory written before the greeter moved onto the
+/// memory manager, so we can refuse to silently reformat it.
+const MEMORY_MANAGER_MAGIC: [u8; 3] = *b"MGR";
+
+/// Wraps raw stable memory in a `MemoryManager`, trapping instead of
+/// silently reformatting it if that memory was written by a pre-MemoryManager
+/// build of this canister (i.e. before per-caller greet counts were added).
+/// `MemoryManager::init` doesn't recognize the legacy `StableCell`-only
+/// layout, so letting it proceed would discard the persisted greeter value
+/// with no error. There's no way to recover that value from this layout, so
+/// the loud failure is the best we can do.
+fn init_memory_manager() -> MemoryManager<DefaultMemoryImpl> {
+    let memory = DefaultMemoryImpl::default();
+
+    if memory.size() > 0 {
+        let mut magic = [0u8; 3];
+        memory.read(0, &mut magic);
+        if magic != MEMORY_MANAGER_MAGIC {
+            ic_cdk::trap(
+                "stable memory predates the MemoryManager-backed greeter and \
+                 cannot be upgraded automatically: continuing would silently \
+                 discard the existing greeter value. Reinstall the canister \
+                 instead of upgrading it.",
+            );
+        }
+    }
+
+    MemoryManager::init(memory)
+}
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(init_memory_manager());
+
+    static GREETER: RefCell<StableCell<String, Memory>> =
+        RefCell::new(StableCell::init(
+            MEMORY_MANAGER.with_borrow(|mm| mm.get(GREETER_MEMORY_ID)),
+            "Hello".to_string(),
+        ).unwrap());
+}
+
+/// Hands out a virtual memory from the canister-wide memory manager, so other
+/// stable structures (the greet counter, the greeting templates, ...) can
+/// keep living alongside the greeter in the same stable memory.
+pub fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with_borrow(|mm| mm.get(id))
+}
+
+/// Canister configuration, persisted as a single unit (as opposed to the
+/// incremental `get`/`set` used for live updates).
+pub struct Config {
+    pub greeter: String,
+}
+
+/// Decouples callers from the concrete persistence engine backing the
+/// greeter, so e.g. unit tests can swap in an in-memory store while
+/// production keeps using stable structures.
+pub trait GreeterStore {
+    fn get(&self) -> String;
+    fn set(&mut self, v: String);
+    fn save_config(&self, cfg: Config);
+}
+
+/// Default [`GreeterStore`] backed by a [`StableCell`].
+#[derive(Default)]
+pub struct StableCellStore;
+
+impl GreeterStore for StableCellStore {
+    fn get(&self) -> String {
+        GREETER.with_borrow(|grt| grt.get().clone())
+    }
+
+    fn set(&mut self, v: String) {
+        GREETER.with_borrow_mut(|grt| grt.set(v)).unwrap();
+    }
+
+    fn save_config(&self, cfg: Config) {
+        GREETER.with_borrow_mut(|grt| grt.set(cfg.greeter)).unwrap();
+    }
+}